@@ -0,0 +1,76 @@
+use std::ops::Range;
+
+// A caret-style diagnostic in the spirit of codespan-reporting/ariadne: a
+// message, the source file it came from, an optional byte range to underline
+// and an optional note. Rendering is deliberately self contained so we don't
+// pull a reporting crate in just for the handful of errors we emit.
+pub struct Diagnostic {
+  pub message: String,
+  pub file: String,
+  pub source: String,
+  pub span: Option<Range<usize>>,
+  pub note: Option<String>,
+}
+
+impl Diagnostic {
+  pub fn new(message: impl Into<String>, file: impl Into<String>, source: impl Into<String>) -> Self {
+    Self {
+      message: message.into(),
+      file: file.into(),
+      source: source.into(),
+      span: None,
+      note: None,
+    }
+  }
+
+  pub fn with_span(mut self, span: Range<usize>) -> Self {
+    self.span = Some(span);
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.note = Some(note.into());
+    self
+  }
+
+  pub fn render(&self) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "error: {}", self.message);
+
+    if let Some(span) = &self.span {
+      let (line_no, line_start, line_end) = locate(&self.source, span.start);
+      let line = &self.source[line_start..line_end];
+      let gutter = line_no.to_string();
+      let pad = " ".repeat(gutter.len());
+      let col = span.start - line_start;
+      let caret = span.end.min(line_end).saturating_sub(span.start).max(1);
+
+      let _ = writeln!(out, "{} --> {}", pad, self.file);
+      let _ = writeln!(out, "{} |", pad);
+      let _ = writeln!(out, "{} | {}", gutter, line);
+      let _ = writeln!(out, "{} | {}{}", pad, " ".repeat(col), "^".repeat(caret));
+    } else {
+      let _ = writeln!(out, " --> {}", self.file);
+    }
+
+    if let Some(note) = &self.note {
+      let _ = writeln!(out, "  = note: {}", note);
+    }
+
+    out
+  }
+}
+
+// Find the 1-based line number and byte bounds of the line containing `offset`.
+fn locate(source: &str, offset: usize) -> (usize, usize, usize) {
+  let offset = offset.min(source.len());
+  let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+  let line_end = source[offset..]
+    .find('\n')
+    .map(|i| offset + i)
+    .unwrap_or(source.len());
+  let line_no = source[..line_start].bytes().filter(|&b| b == b'\n').count() + 1;
+  (line_no, line_start, line_end)
+}
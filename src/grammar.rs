@@ -0,0 +1,395 @@
+use std::ops::Range;
+
+use crate::PDFType;
+
+// A parsed section: its header name, the byte range the header came from and the
+// raw key/value rows (values may be empty and get merged downstream).
+pub struct Section {
+  pub name: String,
+  pub span: Range<usize>,
+  pub body: Vec<(String, String, Range<usize>)>,
+}
+
+// A row the grammar could not make sense of. It carries the line index so the
+// diagnostics subsystem can point the user at it, and is skipped rather than
+// aborting the rest of the document.
+pub struct RowError {
+  pub line: usize,
+  pub span: Range<usize>,
+  pub message: String,
+}
+
+// One line of the extracted text, pre-classified into the tokens the grammar
+// combinators match against: a blank terminator or a row of tab-separated cells
+// (a lone cell is a candidate section header).
+struct Token {
+  line: usize,
+  span: Range<usize>,
+  blank: bool,
+  cells: Vec<String>,
+}
+
+// Cursor over the token stream the combinators consume.
+struct Input {
+  tokens: Vec<Token>,
+  pos: usize,
+}
+
+impl Input {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.pos)
+  }
+
+  fn advance(&mut self) {
+    self.pos += 1;
+  }
+
+  fn next(&mut self) -> Option<&Token> {
+    let tok = self.tokens.get(self.pos);
+    if tok.is_some() {
+      self.pos += 1;
+    }
+    tok
+  }
+}
+
+// The document grammar, expressed as data so a new confirmation type is a new
+// table entry rather than another hand-written `match` arm: the `marker` line
+// identifies the `pdf_type`, and `structural` lists the layout-only lines that
+// sit between real sections and must be ignored.
+struct Grammar {
+  marker: &'static str,
+  pdf_type: PDFType,
+  structural: &'static [&'static str],
+  sections: &'static [&'static str],
+}
+
+const GRAMMARS: &[Grammar] = &[
+  Grammar {
+    marker: "EMPLOYEE STOCK PLAN RELEASE CONFIRMATION",
+    pdf_type: PDFType::RSU,
+    structural: &["Release Details", "Registration:"],
+    sections: &[
+      "Release Summary",
+      "Calculation of Gain",
+      "Stock Distribution",
+      "Cash Distribution",
+    ],
+  },
+  Grammar {
+    marker: "EMPLOYEE STOCK PLAN PURCHASE CONFIRMATION",
+    pdf_type: PDFType::ESPP,
+    structural: &["Purchase Details", "Registration:"],
+    sections: &[
+      "Purchase Summary",
+      "Shares Purchased to Date in Current Offering",
+      "Contributions",
+      "Calculation of Shares Purchased",
+      "Calculation of Gain",
+    ],
+  },
+];
+
+impl Grammar {
+  // True for lines that are part of the page furniture rather than a section:
+  // the type marker itself and any declared structural headings.
+  fn is_structural(&self, line: &str) -> bool {
+    line == self.marker || self.structural.contains(&line)
+  }
+
+  // True for a line that names one of this type's declared sections. Used to
+  // tell a genuine header from a stray single-cell row (a wrapped continuation
+  // or a lone value snapped into a single column band).
+  fn is_section_name(&self, line: &str) -> bool {
+    self.sections.contains(&line)
+  }
+}
+
+// Parse the extracted text into sections, recovering from malformed rows. The
+// returned `PDFType` is whichever grammar's marker was seen (or `Unknown`), and
+// `RowError`s describe every row that was reported and skipped.
+pub fn parse(source: &str) -> (PDFType, Vec<Section>, Vec<RowError>) {
+  let mut input = Input {
+    tokens: tokenize(source),
+    pos: 0,
+  };
+
+  let grammar = detect(&input.tokens);
+  let mut sections = Vec::new();
+  let mut errors = Vec::new();
+
+  // document := section*
+  while let Some(section) = parse_section(&mut input, grammar, &mut errors) {
+    sections.push(section);
+  }
+
+  (grammar.pdf_type, sections, errors)
+}
+
+// Pick the grammar whose marker appears in the stream, defaulting to an Unknown
+// grammar that still skips the shared structural lines.
+fn detect(tokens: &[Token]) -> &'static Grammar {
+  const UNKNOWN: Grammar = Grammar {
+    marker: "",
+    pdf_type: PDFType::Unknown,
+    structural: &["Release Details", "Purchase Details", "Registration:"],
+    sections: &[],
+  };
+
+  for grammar in GRAMMARS {
+    if tokens
+      .iter()
+      .any(|t| t.cells.len() == 1 && t.cells[0] == grammar.marker)
+    {
+      return grammar;
+    }
+  }
+  &UNKNOWN
+}
+
+// section := header body
+//
+// The header parser skips blank and structural lines, and recovers from a
+// key/value row that appears before any header by reporting and dropping it. The
+// body parser accumulates rows until a blank terminator, honouring the E*TRADE
+// quirk where a section's rows are split by a blank line yet continue afterwards.
+fn parse_section(input: &mut Input, grammar: &Grammar, errors: &mut Vec<RowError>) -> Option<Section> {
+  let (name, span) = parse_header(input, grammar, errors)?;
+
+  let mut body = Vec::new();
+  loop {
+    // A section ends at end-of-input, a blank terminator, or the next section's
+    // header. After chunk0-2 the layout engine emits one `\n`-terminated row per
+    // y-cluster and no longer inserts blank rows between sections, so a lone
+    // single-cell row is what re-establishes the boundary; relying on `blank`
+    // alone would swallow every following header as an empty-value key and
+    // collapse the rest of the document into this section.
+    let terminate = match input.peek() {
+      None => true,
+      Some(tok) => tok.blank || is_section_header(&input.tokens, input.pos, grammar),
+    };
+
+    if terminate {
+      // Consume a blank terminator (leaving a header row for `parse_header`),
+      // but honour the E*TRADE quirk where a blank line followed by another
+      // key/value row is an in-section split rather than the section's end.
+      if matches!(input.peek(), Some(tok) if tok.blank) {
+        input.advance();
+        let resume = matches!(input.peek(), Some(next) if !next.blank && next.cells.len() >= 2);
+        if resume {
+          continue;
+        }
+      }
+      break;
+    }
+
+    let tok = input.next().unwrap();
+    if tok.cells[0].is_empty() {
+      // A row whose key is empty carries no field; report and skip it.
+      errors.push(RowError {
+        line: tok.line,
+        span: tok.span.clone(),
+        message: "key/value row with an empty key".into(),
+      });
+      continue;
+    }
+
+    // chunk0-2 emits one cell per page-wide column band, so a right-aligned
+    // value can land in a band past index 1 with empty bands before it (e.g.
+    // `["Award Date", "", "01/02/2020"]`). Take the last non-empty cell as the
+    // value rather than the fixed index 1, which would record an empty value
+    // and get mis-merged with the following row.
+    let value = tok.cells[1..]
+      .iter()
+      .rev()
+      .find(|c| !c.is_empty())
+      .cloned()
+      .unwrap_or_default();
+    body.push((tok.cells[0].clone(), value, tok.span.clone()));
+  }
+
+  Some(Section { name, span, body })
+}
+
+fn parse_header(
+  input: &mut Input,
+  grammar: &Grammar,
+  errors: &mut Vec<RowError>,
+) -> Option<(String, Range<usize>)> {
+  loop {
+    // Classify the next token without holding the borrow across `advance`/`next`.
+    let skip = match input.peek() {
+      None => return None,
+      Some(tok) => tok.blank || (tok.cells.len() == 1 && grammar.is_structural(&tok.cells[0])),
+    };
+    if skip {
+      input.advance();
+      continue;
+    }
+
+    if is_section_header(&input.tokens, input.pos, grammar) {
+      let tok = input.next().unwrap();
+      return Some((tok.cells[0].clone(), tok.span.clone()));
+    }
+
+    // A key/value row with no section to attach to: recover and keep scanning.
+    let tok = input.next().unwrap();
+    errors.push(RowError {
+      line: tok.line,
+      span: tok.span.clone(),
+      message: "key/value row before any section header".into(),
+    });
+  }
+}
+
+// Decide whether the single-cell token at `pos` opens a new section. After
+// chunk0-2's band snapping a wrapped continuation or a lone value can collapse
+// to a single cell, so "one cell" alone is not enough: a single cell is a header
+// only when it names a declared section, or when the next non-blank row is a
+// multi-cell body row it could own.
+fn is_section_header(tokens: &[Token], pos: usize, grammar: &Grammar) -> bool {
+  let tok = match tokens.get(pos) {
+    Some(tok) if !tok.blank && tok.cells.len() == 1 => tok,
+    _ => return false,
+  };
+  if grammar.is_section_name(&tok.cells[0]) {
+    return true;
+  }
+  tokens[pos + 1..]
+    .iter()
+    .find(|t| !t.blank)
+    .is_some_and(|t| t.cells.len() >= 2)
+}
+
+// Split the extracted text into classified tokens, keeping each line's index and
+// byte range in the original source for diagnostics.
+fn tokenize(source: &str) -> Vec<Token> {
+  let mut tokens = Vec::new();
+  let mut start = 0;
+  let mut line = 0;
+
+  let mut push = |raw: &str, span: Range<usize>, line: usize| {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+      tokens.push(Token {
+        line,
+        span,
+        blank: true,
+        cells: Vec::new(),
+      });
+    } else {
+      tokens.push(Token {
+        line,
+        span,
+        blank: false,
+        cells: trimmed.split('\t').map(|c| c.trim().to_string()).collect(),
+      });
+    }
+  };
+
+  let mut emitted = false;
+  for (i, c) in source.char_indices() {
+    if c == '\n' {
+      push(&source[start..i], start..i, line);
+      emitted = true;
+      start = i + 1;
+      line += 1;
+    }
+  }
+  if start < source.len() || !emitted {
+    push(&source[start..], start..source.len(), line);
+  }
+
+  tokens
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RSU_MARKER: &str = "EMPLOYEE STOCK PLAN RELEASE CONFIRMATION";
+
+  // Look a section up by name in a parse result.
+  fn section<'a>(sections: &'a [Section], name: &str) -> &'a Section {
+    sections
+      .iter()
+      .find(|s| s.name == name)
+      .unwrap_or_else(|| panic!("section {:?} not found", name))
+  }
+
+  #[test]
+  fn value_comes_from_last_non_empty_band() {
+    // A right-aligned value snaps into a band past index 1, leaving an empty
+    // interior cell; the value must still be recovered, not dropped.
+    let src = format!("{RSU_MARKER}\nRelease Summary\nAward Date\t\t01/02/2020\n");
+    let (pdf_type, sections, errors) = parse(&src);
+
+    assert!(matches!(pdf_type, PDFType::RSU));
+    assert!(errors.is_empty());
+    let body = &section(&sections, "Release Summary").body;
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].0, "Award Date");
+    assert_eq!(body[0].1, "01/02/2020");
+  }
+
+  #[test]
+  fn known_section_names_split_adjacent_sections() {
+    let src = format!(
+      "{RSU_MARKER}\n\
+       Release Summary\nAward Date\t02/02/2020\n\
+       Calculation of Gain\nMarket Value\t$100.00\n"
+    );
+    let (_, sections, errors) = parse(&src);
+
+    assert!(errors.is_empty());
+    assert_eq!(sections.len(), 2);
+    assert_eq!(section(&sections, "Release Summary").body[0].0, "Award Date");
+    assert_eq!(section(&sections, "Calculation of Gain").body[0].1, "$100.00");
+  }
+
+  #[test]
+  fn lone_cell_is_not_a_bogus_section() {
+    // A wrapped continuation that collapsed to a single cell (no multi-cell row
+    // follows it) stays inside the section instead of inventing a new one.
+    let src = format!(
+      "{RSU_MARKER}\n\
+       Release Summary\nMarket Value Per Share\t$10.00\n(as of release date)\n"
+    );
+    let (_, sections, _) = parse(&src);
+
+    assert_eq!(sections.len(), 1);
+    assert_eq!(section(&sections, "Release Summary").body.len(), 2);
+  }
+
+  #[test]
+  fn row_before_any_header_is_recovered() {
+    let (_, sections, errors) = parse("Stray\tValue\n");
+    assert!(sections.is_empty());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 0);
+    assert!(errors[0].message.contains("before any section header"));
+  }
+
+  #[test]
+  fn malformed_row_is_skipped_without_derailing_rest() {
+    // A stray key/value row before the first section is reported and dropped,
+    // yet the section that follows still parses.
+    let src = format!("Stray\tValue\n{RSU_MARKER}\nRelease Summary\nAward Date\t02/02/2020\n");
+    let (_, sections, errors) = parse(&src);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(sections.len(), 1);
+    assert_eq!(section(&sections, "Release Summary").body[0].1, "02/02/2020");
+  }
+
+  #[test]
+  fn tokenize_tracks_line_and_span() {
+    let tokens = tokenize("a\tb\n\nc\n");
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[0].cells, vec!["a".to_string(), "b".to_string()]);
+    assert_eq!(tokens[0].line, 0);
+    assert_eq!(tokens[0].span, 0..3);
+    assert!(tokens[1].blank);
+    assert_eq!(tokens[2].line, 2);
+  }
+}
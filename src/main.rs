@@ -1,142 +1,222 @@
-use std::{collections::HashMap, error::Error, io};
+use std::{collections::HashMap, error::Error, io, ops::Range};
 
+mod diagnostic;
+mod grammar;
 mod pdf;
 
+use diagnostic::Diagnostic;
+use grammar::Section;
+
 #[derive(Eq, Hash, PartialEq, Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 enum PDFType {
   Unknown,
   ESPP,
   RSU,
 }
 
-// Function pointer definition must be wrapped in a struct to be recursive
-struct StateFunction(fn(&mut Parser) -> Option<StateFunction>);
-
-struct Section {
-  name: String,
-  body: Vec<(String, String)>,
-}
-
+// A parsed document: the grammar hands back the sections and any rows it had to
+// recover from, and we keep the source text and file name so both missing-field
+// lookups and recovered-row reports can be rendered as diagnostics.
 pub struct Parser {
-  lines: Vec<String>,
+  source: String,
+  file: String,
   data: Vec<Section>,
+  errors: Vec<grammar::RowError>,
   pdf_type: PDFType,
-  pos: usize,
-  current_section: String,
-  current_body: Vec<(String, String)>,
 }
 
 impl Parser {
-  fn new(input: &str) -> Self {
+  fn new(input: &str, file: impl Into<String>) -> Self {
+    let (pdf_type, data, errors) = grammar::parse(input);
     Self {
-      lines: input.lines().map(|v| v.trim().to_string()).collect(),
-      data: Vec::new(),
-      pdf_type: PDFType::Unknown,
-      pos: 0,
-      current_section: "".into(),
-      current_body: Vec::new(),
+      source: input.to_string(),
+      file: file.into(),
+      data,
+      errors,
+      pdf_type,
     }
   }
 
-  fn parse(&mut self) {
-    let mut state = Some(StateFunction(Parser::parse_section));
-    while let Some(next_state) = state {
-      state = next_state.0(self)
-    }
+  // Turn every row the grammar recovered from into a diagnostic anchored at the
+  // offending line.
+  fn row_diagnostics(&self) -> Vec<Diagnostic> {
+    self
+      .errors
+      .iter()
+      .map(|e| {
+        Diagnostic::new(
+          format!("skipped malformed row: {}", e.message),
+          &self.file,
+          &self.source,
+        )
+        .with_span(e.span.clone())
+        .with_note(format!("line {}", e.line + 1))
+      })
+      .collect()
   }
 
-  fn next(&mut self) -> Option<String> {
-    if self.pos >= self.lines.len() {
-      None
-    } else {
-      let l = self.lines[self.pos].clone();
-      self.pos += 1;
-      Some(l)
-    }
-  }
+  // Collapse the parsed sections into a lookup structure that remembers where
+  // each section header came from, so a missing field can be reported against
+  // the source text.
+  fn into_section_map(self) -> SectionMap {
+    let mut sections: HashMap<String, SectionEntry> = HashMap::new();
+    for section in self.data {
+      let entry = sections.entry(section.name).or_insert_with(|| SectionEntry {
+        span: section.span.clone(),
+        fields: HashMap::new(),
+      });
 
-  fn peek(&mut self) -> Option<String> {
-    if self.pos >= self.lines.len() - 1 {
-      None
-    } else {
-      let l = self.lines[self.pos].clone();
-      Some(l)
-    }
-  }
+      let mut body_iter = section.body.iter().peekable();
+      while body_iter.peek().is_some() {
+        let (name, value, _) = body_iter.next().cloned().unwrap();
 
-  fn skip_empty_lines(&mut self) {
-    while self.pos < self.lines.len() {
-      if self.lines[self.pos] != "" {
-        return;
+        // This occurs when we have a continuation on a newline of some key/value.
+        if value.is_empty() && body_iter.peek().is_some() {
+          let (name2, value2, _) = body_iter.next().cloned().unwrap();
+          // We don't care if the name starts with a parenthesis.
+          if name2.starts_with("(") {
+            entry.fields.insert(name, value2);
+          } else {
+            entry.fields.insert(format!("{} {}", name, name2), value2);
+          }
+        } else {
+          entry.fields.insert(name, value);
+        }
       }
-      self.pos += 1;
     }
-  }
 
-  fn parse_section(p: &mut Parser) -> Option<StateFunction> {
-    while let Some(l) = p.next() {
-      match l.as_str() {
-        "EMPLOYEE STOCK PLAN RELEASE CONFIRMATION" => {
-          p.pdf_type = PDFType::RSU;
-          ()
-        },
-        "EMPLOYEE STOCK PLAN PURCHASE CONFIRMATION" => {
-          p.pdf_type = PDFType::ESPP;
-          ()
-        },
-        "Release Details" => (),
-        "Registration:" => (),
-        "Purchase Details" => (),
-        "" => (),
-        _ => {
-          p.skip_empty_lines();
-          p.current_section = l;
-          return Some(StateFunction(Parser::parse_section_body));
-        },
-      };
+    SectionMap {
+      file: self.file,
+      source: self.source,
+      sections,
     }
-
-    None
   }
+}
 
-  fn parse_section_body(p: &mut Parser) -> Option<StateFunction> {
-    while let Some(l) = p.next() {
-      // println!("{} -> {:?}", l, p.peek());
-      if l == "" {
-        // This happens with some PDFs where we have a section but
-        // there are gaps within the section.
-        if let Some(p) = p.peek() {
-          if p.contains("\t") {
-            return Some(StateFunction(Parser::parse_section_body));
-          }
-        }
+struct SectionEntry {
+  span: Range<usize>,
+  fields: HashMap<String, String>,
+}
+
+struct SectionMap {
+  file: String,
+  source: String,
+  sections: HashMap<String, SectionEntry>,
+}
+
+impl SectionMap {
+  // Look up a field, returning a rich diagnostic rather than panicking when the
+  // section or key is absent from this particular PDF layout.
+  fn get(&self, section: &str, key: &str) -> Result<&str, Diagnostic> {
+    let entry = match self.sections.get(section) {
+      Some(entry) => entry,
+      None => {
+        let mut names: Vec<&str> = self.sections.keys().map(|s| s.as_str()).collect();
+        names.sort_unstable();
+        return Err(
+          Diagnostic::new(
+            format!("missing section {:?}", section),
+            &self.file,
+            &self.source,
+          )
+          .with_note(format!("sections found: {}", names.join(", "))),
+        );
+      },
+    };
+
+    match entry.fields.get(key) {
+      Some(value) => Ok(value.as_str()),
+      None => {
+        let mut keys: Vec<&str> = entry.fields.keys().map(|s| s.as_str()).collect();
+        keys.sort_unstable();
+        Err(
+          Diagnostic::new(
+            format!("missing field {:?} in section {:?}", key, section),
+            &self.file,
+            &self.source,
+          )
+          .with_span(entry.span.clone())
+          .with_note(format!("fields found here: {}", keys.join(", "))),
+        )
+      },
+    }
+  }
 
-        p.data.push(Section {
-          name: p.current_section.clone(),
-          body: p.current_body.clone(),
-        });
-        p.current_section = "".into();
-        p.current_body = vec![];
-        return Some(StateFunction(Parser::parse_section));
+  // Resolve a whole record at once, collecting every miss so one pass over a PDF
+  // reports all of its problems instead of only the first.
+  fn record(&self, fields: &[(&str, &str)]) -> Result<Vec<String>, Vec<Diagnostic>> {
+    let mut values = Vec::with_capacity(fields.len());
+    let mut errors = Vec::new();
+    for (section, key) in fields {
+      match self.get(section, key) {
+        Ok(value) => values.push(value.to_string()),
+        Err(diag) => errors.push(diag),
       }
+    }
 
-      let parts: Vec<String> = l.split("\t").map(|v| v.trim().to_string()).collect();
-      // println!("{:#?}", parts);
-      p.current_body.push((
-        parts[0].clone(),
-        parts
-          .get(1)
-          .map(|v| v.to_owned())
-          .unwrap_or_else(|| "".to_owned()),
-      ));
+    if errors.is_empty() {
+      Ok(values)
+    } else {
+      Err(errors)
     }
+  }
+}
 
-    None
+// Pull the value of a `--password <value>` (or `--password=<value>`) flag out of
+// the process arguments, if present.
+fn password_from_args() -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    if let Some(value) = arg.strip_prefix("--password=") {
+      return Some(value.to_string());
+    }
+    if arg == "--password" {
+      return args.next();
+    }
   }
+  None
 }
 
+const RSU_FIELDS: [(&str, &str); 12] = [
+  ("Release Summary", "Award Date"),
+  ("Release Summary", "Release Date"),
+  ("Release Summary", "Shares Released"),
+  ("Release Summary", "Market Value Per Share"),
+  ("Release Summary", "Sale Price Per Share"),
+  ("Calculation of Gain", "Market Value"),
+  ("Stock Distribution", "Shares Sold"),
+  ("Stock Distribution", "Shares Issued"),
+  ("Cash Distribution", "Total Sale Price"),
+  ("Cash Distribution", "Total Tax"),
+  ("Cash Distribution", "Fee"),
+  ("Cash Distribution", "Total Due Participant"),
+];
+
+const ESPP_FIELDS: [(&str, &str); 14] = [
+  ("Purchase Summary", "Grant Date"),
+  ("Purchase Summary", "Purchase Begin Date"),
+  ("Purchase Summary", "Purchase Date"),
+  ("Shares Purchased to Date in Current Offering", "Shares Purchased"),
+  ("Contributions", "Previous Carry Forward"),
+  ("Contributions", "Current Contributions"),
+  ("Contributions", "Total Contributions"),
+  ("Contributions", "Total Price"),
+  ("Contributions", "Amount Refunded"),
+  ("Calculation of Shares Purchased", "Grant Date Market Value"),
+  ("Calculation of Shares Purchased", "Purchase Value per Share"),
+  ("Calculation of Shares Purchased", "Purchase Price per Share"),
+  ("Calculation of Gain", "Total Value"),
+  ("Calculation of Gain", "Taxable Gain"),
+];
+
 fn main() -> Result<(), Box<dyn Error>> {
   let mut pdf_map: HashMap<PDFType, Vec<Parser>> = HashMap::new();
+  let mut failures: Vec<Diagnostic> = Vec::new();
+
+  // Password for encrypted PDFs: a `--password <value>` flag wins, otherwise fall
+  // back to the `ETRADE_PDF_PASSWORD` environment variable.
+  let password = password_from_args().or_else(|| std::env::var("ETRADE_PDF_PASSWORD").ok());
 
   for entry in glob::glob("./input/*.pdf").expect("failed to read glob pattern") {
     let path = match entry {
@@ -147,12 +227,22 @@ fn main() -> Result<(), Box<dyn Error>> {
       },
     };
 
-    let bytes = std::fs::read(path)?;
-    let out = pdf::extract(&bytes)?;
-    // println!("{}", out);
+    let bytes = std::fs::read(&path)?;
+    let out = match pdf::extract(&bytes, password.as_deref()) {
+      Ok(out) => out,
+      Err(e) => {
+        // Don't abort the whole run over one unreadable PDF; record it and move on.
+        failures.push(Diagnostic::new(
+          e.to_string(),
+          path.display().to_string(),
+          String::new(),
+        ));
+        continue;
+      },
+    };
 
-    let mut parser = Parser::new(&out);
-    parser.parse();
+    let parser = Parser::new(&out, path.display().to_string());
+    failures.extend(parser.row_diagnostics());
 
     let entry = pdf_map.entry(parser.pdf_type).or_default();
     entry.push(parser);
@@ -163,7 +253,7 @@ fn main() -> Result<(), Box<dyn Error>> {
       PDFType::RSU => {
         let mut wtr = csv::Writer::from_writer(io::stdout());
 
-        wtr.write_record(&[
+        wtr.write_record([
           "Award Date",
           "Release Date",
           "Shares Released",
@@ -179,21 +269,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         ])?;
 
         for parser in parsers {
-          let data = sections_to_map(parser.data);
-          wtr.write_record(&[
-            &data["Release Summary"]["Award Date"],
-            &data["Release Summary"]["Release Date"],
-            &data["Release Summary"]["Shares Released"],
-            &data["Release Summary"]["Market Value Per Share"],
-            &data["Release Summary"]["Sale Price Per Share"],
-            &data["Calculation of Gain"]["Market Value"],
-            &data["Stock Distribution"]["Shares Sold"],
-            &data["Stock Distribution"]["Shares Issued"],
-            &data["Cash Distribution"]["Total Sale Price"],
-            &data["Cash Distribution"]["Total Tax"],
-            &data["Cash Distribution"]["Fee"],
-            &data["Cash Distribution"]["Total Due Participant"],
-          ])?;
+          match parser.into_section_map().record(&RSU_FIELDS) {
+            Ok(record) => wtr.write_record(&record)?,
+            Err(mut diags) => failures.append(&mut diags),
+          }
         }
 
         wtr.flush()?;
@@ -202,7 +281,7 @@ fn main() -> Result<(), Box<dyn Error>> {
       PDFType::ESPP => {
         let mut wtr = csv::Writer::from_writer(io::stdout());
 
-        wtr.write_record(&[
+        wtr.write_record([
           "Grant Date",
           "Purchase Begin Date",
           "Purchase Date",
@@ -220,23 +299,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         ])?;
 
         for parser in parsers {
-          let data = sections_to_map(parser.data);
-          wtr.write_record(&[
-            &data["Purchase Summary"]["Grant Date"],
-            &data["Purchase Summary"]["Purchase Begin Date"],
-            &data["Purchase Summary"]["Purchase Date"],
-            &data["Shares Purchased to Date in Current Offering"]["Shares Purchased"],
-            &data["Contributions"]["Previous Carry Forward"],
-            &data["Contributions"]["Current Contributions"],
-            &data["Contributions"]["Total Contributions"],
-            &data["Contributions"]["Total Price"],
-            &data["Contributions"]["Amount Refunded"],
-            &data["Calculation of Shares Purchased"]["Grant Date Market Value"],
-            &data["Calculation of Shares Purchased"]["Purchase Value per Share"],
-            &data["Calculation of Shares Purchased"]["Purchase Price per Share"],
-            &data["Calculation of Gain"]["Total Value"],
-            &data["Calculation of Gain"]["Taxable Gain"],
-          ])?;
+          match parser.into_section_map().record(&ESPP_FIELDS) {
+            Ok(record) => wtr.write_record(&record)?,
+            Err(mut diags) => failures.append(&mut diags),
+          }
         }
 
         wtr.flush()?;
@@ -248,36 +314,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
   }
 
-  Ok(())
-}
-
-fn sections_to_map(sections: Vec<Section>) -> HashMap<String, HashMap<String, String>> {
-  let mut map = HashMap::new();
-  for section in sections {
-    let entry: &mut HashMap<String, String> = map.entry(section.name).or_default();
-    let mut body_iter = section.body.iter().peekable();
-    while body_iter.peek().is_some() {
-      let (name, value) = body_iter
-        .next()
-        .map(|(n, v)| (n.clone(), v.clone()))
-        .unwrap();
-
-      // This occurs when we have a continuation on a newline of some key/value.
-      if value.is_empty() && body_iter.peek().is_some() {
-        let (name2, value2) = body_iter
-          .next()
-          .map(|(n, v)| (n.clone(), v.clone()))
-          .unwrap();
-        // We don't care if the name starts with a parenthesis.
-        if name2.starts_with("(") {
-          entry.insert(name, value2);
-        } else {
-          entry.insert(format!("{} {}", name, name2), value2);
-        }
-      } else {
-        entry.insert(name, value);
-      }
+  if !failures.is_empty() {
+    eprintln!("\n{} problem(s) reported:\n", failures.len());
+    for diag in &failures {
+      eprintln!("{}", diag.render());
     }
   }
-  map
+
+  Ok(())
 }
@@ -1,16 +1,26 @@
 use std::error::Error;
+use std::fmt;
 
 use euclid::Transform2D;
-use lopdf::Document;
+use lopdf::encryption::DecryptionError;
+use lopdf::{Document, Error as LopdfError};
 use pdf_extract::{self, output_doc, ConvertToFmt, MediaBox, OutputDev, OutputError, Transform};
 
 type ArtBox = (f64, f64, f64, f64);
 
+// A single glyph positioned on the page, captured during `output_character` and
+// laid out into rows and columns once the whole page is known.
+struct Glyph {
+  x: f64,
+  y: f64,
+  width: f64,
+  font_size: f64,
+  char: String,
+}
+
 struct PlainTextOutput<W: ConvertToFmt> {
   writer: W::Writer,
-  last_end: f64,
-  last_y: f64,
-  first_char: bool,
+  glyphs: Vec<Glyph>,
   flip_ctm: Transform,
 }
 
@@ -18,16 +28,16 @@ impl<W: ConvertToFmt> PlainTextOutput<W> {
   fn new(writer: W) -> PlainTextOutput<W> {
     PlainTextOutput {
       writer: writer.convert(),
-      last_end: 100000.,
-      first_char: false,
-      last_y: 0.,
+      glyphs: Vec::new(),
       flip_ctm: Transform::identity(),
     }
   }
 }
 
-// There are some structural hints that PDFs can use to signal word and line endings:
-// however relying on these is not likely to be sufficient.
+// Relying on the PDF's structural hints for word and line endings is not
+// sufficient, so we reconstruct the table geometry ourselves: glyphs are
+// buffered per page and clustered into rows and columns in `end_page`, which
+// produces stable TSV the `Parser` can split on tabs.
 impl<W: pdf_extract::ConvertToFmt> OutputDev for PlainTextOutput<W> {
   fn begin_page(
     &mut self,
@@ -36,10 +46,64 @@ impl<W: pdf_extract::ConvertToFmt> OutputDev for PlainTextOutput<W> {
     _: Option<ArtBox>,
   ) -> Result<(), OutputError> {
     self.flip_ctm = Transform2D::row_major(1., 0., 0., -1., 0., media_box.ury - media_box.lly);
+    self.glyphs.clear();
     Ok(())
   }
 
   fn end_page(&mut self) -> Result<(), OutputError> {
+    use std::fmt::Write;
+
+    if self.glyphs.is_empty() {
+      return Ok(());
+    }
+
+    let bands = column_bands(&self.glyphs);
+
+    // Cluster glyphs into rows by their y position. Glyphs are visited in y order;
+    // their order within a row is set by the x sort below.
+    let mut order: Vec<usize> = (0..self.glyphs.len()).collect();
+    order.sort_by(|&a, &b| {
+      self.glyphs[a]
+        .y
+        .partial_cmp(&self.glyphs[b].y)
+        .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    let mut row: Vec<usize> = Vec::new();
+    let mut row_y = self.glyphs[order[0]].y;
+    for &i in &order {
+      let g = &self.glyphs[i];
+      if !row.is_empty() && (g.y - row_y).abs() > g.font_size * 0.5 {
+        rows.push(std::mem::take(&mut row));
+      }
+      if row.is_empty() {
+        row_y = g.y;
+      }
+      row.push(i);
+    }
+    if !row.is_empty() {
+      rows.push(row);
+    }
+
+    for mut row in rows {
+      // Emit glyphs left to right within the row.
+      row.sort_by(|&a, &b| {
+        self.glyphs[a]
+          .x
+          .partial_cmp(&self.glyphs[b].x)
+          .unwrap_or(std::cmp::Ordering::Equal)
+      });
+
+      let mut cells = vec![String::new(); bands.len().max(1)];
+      for &i in &row {
+        let g = &self.glyphs[i];
+        cells[nearest_band(&bands, g.x)].push_str(&g.char);
+      }
+
+      writeln!(self.writer, "{}", cells.join("\t"))?;
+    }
+
     Ok(())
   }
 
@@ -56,40 +120,18 @@ impl<W: pdf_extract::ConvertToFmt> OutputDev for PlainTextOutput<W> {
     // get the length of one sized of the square with the same area with a rectangle of size (x, y)
     let transformed_font_size = (transformed_font_size_vec.x * transformed_font_size_vec.y).sqrt();
     let (x, y) = (position.m31, position.m32);
-    use std::fmt::Write;
-
-    // println!("{}", char);
-    if self.first_char {
-      if (y - self.last_y).abs() > transformed_font_size * 1.5 {
-        write!(self.writer, "\n")?;
-      }
-
-      // we've moved to the left and down
-      if x < self.last_end && (y - self.last_y).abs() > transformed_font_size * 0.5 {
-        write!(self.writer, "\n")?;
-      }
 
-      // we've moved to the next column
-      if x > self.last_end && y < self.last_y {
-        write!(self.writer, "\n")?;
-      }
-
-      // we've moved a good amount to the right
-      if x > self.last_end + transformed_font_size * 0.1 {
-        write!(self.writer, "\t")?;
-      }
-    }
-
-    // let norm = unicode_normalization::UnicodeNormalization::nfkc(char);
-    write!(self.writer, "{}", char)?;
-    self.first_char = false;
-    self.last_y = y;
-    self.last_end = x + width * transformed_font_size;
+    self.glyphs.push(Glyph {
+      x,
+      y,
+      width: width * transformed_font_size,
+      font_size: transformed_font_size,
+      char: char.to_string(),
+    });
     Ok(())
   }
 
   fn begin_word(&mut self) -> Result<(), OutputError> {
-    self.first_char = true;
     Ok(())
   }
 
@@ -98,15 +140,136 @@ impl<W: pdf_extract::ConvertToFmt> OutputDev for PlainTextOutput<W> {
   }
 
   fn end_line(&mut self) -> Result<(), OutputError> {
-    // write!(self.file, "\n");
     Ok(())
   }
 }
 
-pub(crate) fn extract(bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+// Derive the table's column boundaries from the x-start of every glyph on the
+// page: sort the positions and cut wherever the gap between neighbours exceeds
+// roughly half the median glyph width. Each returned value is the left edge of a
+// column band. A page whose glyphs never gap that much yields a single band.
+fn column_bands(glyphs: &[Glyph]) -> Vec<f64> {
+  let mut widths: Vec<f64> = glyphs.iter().map(|g| g.width).filter(|w| *w > 0.).collect();
+  widths.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+  let median = if widths.is_empty() {
+    0.
+  } else {
+    widths[widths.len() / 2]
+  };
+  let threshold = median * 0.5;
+
+  let mut xs: Vec<f64> = glyphs.iter().map(|g| g.x).collect();
+  xs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+  let mut bands = vec![xs[0]];
+  for w in xs.windows(2) {
+    if w[1] - w[0] > threshold {
+      bands.push(w[1]);
+    }
+  }
+  bands
+}
+
+// Index of the band whose left edge is closest to `x`. A glyph that starts
+// before the first band snaps to it rather than falling off the front.
+fn nearest_band(bands: &[f64], x: f64) -> usize {
+  let mut best = 0;
+  let mut best_dist = f64::INFINITY;
+  for (i, &edge) in bands.iter().enumerate() {
+    let dist = (x - edge).abs();
+    if dist < best_dist {
+      best_dist = dist;
+      best = i;
+    }
+  }
+  best
+}
+
+// A decryption failure the user can act on: either they supplied the wrong
+// password, or the PDF uses a security handler we don't implement.
+#[derive(Debug)]
+pub(crate) enum DecryptError {
+  WrongPassword,
+  Unsupported(String),
+}
+
+impl fmt::Display for DecryptError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecryptError::WrongPassword => write!(
+        f,
+        "wrong password for encrypted PDF (set ETRADE_PDF_PASSWORD or pass --password)"
+      ),
+      DecryptError::Unsupported(detail) => {
+        write!(f, "unsupported PDF encryption: {}", detail)
+      },
+    }
+  }
+}
+
+impl Error for DecryptError {}
+
+// Decrypt the document's content streams in place using the standard security
+// handler (RC4 and AES-128/256 key derivation). An empty password is tried when
+// none is supplied, which covers owner-only encrypted PDFs.
+fn decrypt(doc: &mut Document, password: Option<&str>) -> Result<(), DecryptError> {
+  let password = password.unwrap_or("");
+  match doc.decrypt(password) {
+    Ok(()) => Ok(()),
+    Err(LopdfError::Decryption(DecryptionError::IncorrectPassword)) => {
+      Err(DecryptError::WrongPassword)
+    },
+    Err(e) => Err(DecryptError::Unsupported(e.to_string())),
+  }
+}
+
+pub(crate) fn extract(bytes: &[u8], password: Option<&str>) -> Result<String, Box<dyn Error>> {
   let mut out = String::new();
   let mut output = PlainTextOutput::new(&mut out);
-  let doc = Document::load_mem(&bytes)?;
+  let mut doc = Document::load_mem(bytes)?;
+  // Encrypted confirmations carry an `/Encrypt` dictionary in the trailer; they
+  // must be decrypted before any text can be extracted.
+  if doc.trailer.has(b"Encrypt") {
+    decrypt(&mut doc, password)?;
+  }
   output_doc(&doc, &mut output)?;
   Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn glyph(x: f64, width: f64) -> Glyph {
+    Glyph {
+      x,
+      y: 0.,
+      width,
+      font_size: 10.,
+      char: "x".to_string(),
+    }
+  }
+
+  #[test]
+  fn column_bands_cuts_on_wide_gaps() {
+    // Two tight clusters (median width 5 ⇒ threshold 2.5) separated by a big
+    // gap yield one band per cluster, left edges first.
+    let glyphs = [glyph(10., 5.), glyph(11., 5.), glyph(100., 5.), glyph(101., 5.)];
+    assert_eq!(column_bands(&glyphs), vec![10., 100.]);
+  }
+
+  #[test]
+  fn column_bands_single_column_has_no_cuts() {
+    let glyphs = [glyph(10., 5.), glyph(11., 5.), glyph(12., 5.)];
+    assert_eq!(column_bands(&glyphs), vec![10.]);
+  }
+
+  #[test]
+  fn nearest_band_snaps_to_closest_edge() {
+    let bands = [10., 100.];
+    assert_eq!(nearest_band(&bands, 12.), 0);
+    assert_eq!(nearest_band(&bands, 98.), 1);
+    // A glyph starting before the first band snaps to it rather than falling off.
+    assert_eq!(nearest_band(&bands, 3.), 0);
+  }
+}